@@ -0,0 +1,207 @@
+/// Sample rate (Hz) that Songbird hands us when `DecodeMode::Decode` is active.
+pub const INPUT_SAMPLE_RATE: u32 = 48000;
+/// Number of interleaved channels in the decoded Discord PCM (stereo).
+pub const INPUT_CHANNELS: usize = 2;
+
+/// Sample rate (Hz) expected by the Google Speech recognizer and the WAV files we write.
+pub const OUTPUT_SAMPLE_RATE: u32 = 16000;
+/// We only ever emit mono audio downstream.
+pub const OUTPUT_CHANNELS: u16 = 1;
+
+/// Average each L/R pair of the interleaved stereo stream into a single mono sample.
+pub fn downmix_stereo_to_mono(samples: &[i16]) -> Vec<i16> {
+    samples
+        .chunks_exact(INPUT_CHANNELS)
+        .map(|pair| ((pair[0] as i32 + pair[1] as i32) / 2) as i16)
+        .collect()
+}
+
+/// Resample mono PCM from 48 kHz down to 16 kHz using a cheap 3:1 decimation: a
+/// 3-tap moving-average low-pass to knock down energy above the new Nyquist
+/// frequency, followed by picking every third sample.
+pub fn resample_48k_to_16k_mono(samples: &[i16]) -> Vec<i16> {
+    const RATIO: usize = (INPUT_SAMPLE_RATE / OUTPUT_SAMPLE_RATE) as usize;
+
+    let mut out = Vec::with_capacity(samples.len() / RATIO);
+    let mut i = 0;
+    while i + RATIO <= samples.len() {
+        let window = &samples[i..i + RATIO];
+        let sum: i32 = window.iter().map(|&s| s as i32).sum();
+        out.push((sum / RATIO as i32) as i16);
+        i += RATIO;
+    }
+
+    out
+}
+
+/// Downmix decoded Discord PCM (48 kHz stereo) into the 16 kHz mono stream that the
+/// WAV writer and the recognizer both expect.
+pub fn to_recognizer_pcm(samples: &[i16]) -> Vec<i16> {
+    resample_48k_to_16k_mono(&downmix_stereo_to_mono(samples))
+}
+
+/// Samples in one 20 ms frame of the 16 kHz mono recognizer stream.
+pub const VAD_FRAME_SAMPLES: usize = 320;
+/// ~300 ms of hangover, expressed in frames, so a short pause mid-word
+/// doesn't get classified as silence.
+const VAD_HANGOVER_FRAMES: usize = 15;
+/// How many consecutive silent frames (after hangover) it takes to consider
+/// an utterance finished.
+pub const VAD_SILENCE_FRAMES_TO_FINALIZE: usize = VAD_HANGOVER_FRAMES;
+/// A frame counts as speech once its RMS energy exceeds the adaptive noise
+/// floor by this factor.
+const VAD_THRESHOLD_FACTOR: f32 = 2.5;
+/// Floor under the adaptive threshold so near-total silence at stream start
+/// (noise_floor == 0) doesn't count as speech.
+const VAD_MIN_THRESHOLD: f32 = 50.0;
+/// How quickly the noise floor tracks ambient energy between utterances.
+const VAD_NOISE_FLOOR_ALPHA: f32 = 0.05;
+
+fn rms(frame: &[i16]) -> f32 {
+    if frame.is_empty() {
+        return 0.0;
+    }
+
+    let sum_sq: f64 = frame.iter().map(|&s| (s as f64) * (s as f64)).sum();
+    (sum_sq / frame.len() as f64).sqrt() as f32
+}
+
+/// Energy-based voice-activity detector: classifies 20 ms frames as speech or
+/// silence against a slowly adapting noise floor, with a hangover so brief
+/// gaps mid-word aren't cut, and tracks consecutive silent frames so callers
+/// can decide when an utterance is actually over.
+pub struct Vad {
+    noise_floor: f32,
+    hangover_remaining: usize,
+    silence_run: usize,
+}
+
+impl Vad {
+    pub fn new() -> Self {
+        Self {
+            noise_floor: 0.0,
+            hangover_remaining: 0,
+            silence_run: 0,
+        }
+    }
+
+    /// Classifies one `VAD_FRAME_SAMPLES`-sample frame, returning `true` if it
+    /// should be treated as speech (including hangover frames).
+    pub fn classify_frame(&mut self, frame: &[i16]) -> bool {
+        let energy = rms(frame);
+        let threshold = (self.noise_floor * VAD_THRESHOLD_FACTOR).max(VAD_MIN_THRESHOLD);
+        let above_threshold = energy > threshold;
+
+        let is_speech = if above_threshold {
+            self.hangover_remaining = VAD_HANGOVER_FRAMES;
+            true
+        } else if self.hangover_remaining > 0 {
+            self.hangover_remaining -= 1;
+            true
+        } else {
+            // Only adapt the floor once we're confident we're past any hangover,
+            // so speech energy never drags the floor upward.
+            self.noise_floor += (energy - self.noise_floor) * VAD_NOISE_FLOOR_ALPHA;
+            false
+        };
+
+        if is_speech {
+            self.silence_run = 0;
+        } else {
+            self.silence_run += 1;
+        }
+
+        is_speech
+    }
+
+    /// Whether enough sustained silence has passed to finalize the utterance.
+    pub fn utterance_ended(&self) -> bool {
+        self.silence_run >= VAD_SILENCE_FRAMES_TO_FINALIZE
+    }
+
+    pub fn reset_utterance(&mut self) {
+        self.silence_run = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downmix_averages_left_and_right_channels() {
+        let stereo = [10i16, 20, -10, -20, 0, 0];
+        assert_eq!(downmix_stereo_to_mono(&stereo), vec![15, -15, 0]);
+    }
+
+    #[test]
+    fn downmix_drops_a_trailing_incomplete_pair() {
+        let stereo = [10i16, 20, 30];
+        assert_eq!(downmix_stereo_to_mono(&stereo), vec![15]);
+    }
+
+    #[test]
+    fn resample_keeps_one_sample_in_three_and_averages_the_window() {
+        let mono_48k = [100i16, 200, 300, 0, 0, 0];
+        assert_eq!(resample_48k_to_16k_mono(&mono_48k), vec![200, 0]);
+    }
+
+    #[test]
+    fn resample_drops_a_trailing_partial_window() {
+        let mono_48k = [100i16, 200, 300, 400];
+        assert_eq!(resample_48k_to_16k_mono(&mono_48k), vec![200]);
+    }
+
+    fn tone_frame() -> Vec<i16> {
+        vec![1000i16; VAD_FRAME_SAMPLES]
+    }
+
+    fn silence_frame() -> Vec<i16> {
+        vec![0i16; VAD_FRAME_SAMPLES]
+    }
+
+    #[test]
+    fn vad_classifies_a_loud_frame_as_speech() {
+        let mut vad = Vad::new();
+        assert!(vad.classify_frame(&tone_frame()));
+        assert!(!vad.utterance_ended());
+    }
+
+    #[test]
+    fn vad_holds_through_hangover_before_ending_the_utterance() {
+        let mut vad = Vad::new();
+        assert!(vad.classify_frame(&tone_frame()));
+
+        // Hangover frames are still reported as speech, and don't count
+        // towards ending the utterance yet.
+        for _ in 0..VAD_HANGOVER_FRAMES {
+            assert!(vad.classify_frame(&silence_frame()));
+            assert!(!vad.utterance_ended());
+        }
+
+        // Once hangover is spent, sustained silence is classified as silence
+        // and, after enough consecutive frames, ends the utterance.
+        for _ in 0..VAD_SILENCE_FRAMES_TO_FINALIZE - 1 {
+            assert!(!vad.classify_frame(&silence_frame()));
+            assert!(!vad.utterance_ended());
+        }
+        assert!(!vad.classify_frame(&silence_frame()));
+        assert!(vad.utterance_ended());
+    }
+
+    #[test]
+    fn vad_reset_utterance_clears_the_silence_run_only() {
+        let mut vad = Vad::new();
+        vad.classify_frame(&tone_frame());
+        for _ in 0..(VAD_HANGOVER_FRAMES + VAD_SILENCE_FRAMES_TO_FINALIZE) {
+            vad.classify_frame(&silence_frame());
+        }
+        assert!(vad.utterance_ended());
+
+        vad.reset_utterance();
+        assert!(!vad.utterance_ended());
+
+        // A fresh loud frame is still classified as speech afterwards.
+        assert!(vad.classify_frame(&tone_frame()));
+    }
+}
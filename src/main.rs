@@ -23,9 +23,53 @@ use songbird::{
 use std::collections::HashMap;
 use std::env;
 use std::sync::Arc;
+use tokio::sync::mpsc;
 mod audio;
+mod opus_record;
 mod recognize;
-use recognize::{GoogleSpeechRecognizer, VoiceRecognizer};
+mod sink;
+use recognize::{GoogleSpeechRecognizer, StreamInput, StreamTranscript, VoiceRecognizer};
+use sink::AudioSink;
+
+/// How a voice session captures audio, selected when joining a channel.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CaptureMode {
+    /// Decode to PCM for recognition (the default); WAV/recognizer pipeline applies.
+    Decode,
+    /// Keep Songbird in `DecodeMode::Decrypt` and archive the raw Opus payloads
+    /// byte-for-byte instead of recognizing them.
+    Passthrough,
+}
+
+impl CaptureMode {
+    fn from_arg(arg: Option<&str>) -> Self {
+        match arg {
+            Some("opus") | Some("passthrough") => CaptureMode::Passthrough,
+            _ => CaptureMode::Decode,
+        }
+    }
+
+    fn decode_mode(self) -> DecodeMode {
+        match self {
+            CaptureMode::Decode => DecodeMode::Decode,
+            CaptureMode::Passthrough => DecodeMode::Decrypt,
+        }
+    }
+}
+
+/// Which path a speaker's audio is currently routed through: a live
+/// recognition stream, or the buffer-then-batch-recognize fallback.
+enum RecognitionRoute {
+    Streaming(mpsc::UnboundedSender<StreamInput>),
+    Batch,
+}
+
+/// Per-SSRC voice-activity state: the detector itself, plus samples not yet
+/// long enough to make up a full `audio::VAD_FRAME_SAMPLES` frame.
+struct VadSession {
+    vad: audio::Vad,
+    carry: Vec<i16>,
+}
 
 struct Handler;
 
@@ -40,6 +84,18 @@ struct State {
     audio_buffer_map: Arc<Mutex<HashMap<u32, Vec<i16>>>>,
     users_ssrc_map: Arc<Mutex<HashMap<u32, String>>>,
     recognizer: Arc<Mutex<dyn VoiceRecognizer + Send + Sync>>,
+    recognition_routes: Arc<Mutex<HashMap<u32, RecognitionRoute>>>,
+    vad_sessions: Arc<Mutex<HashMap<u32, VadSession>>>,
+    // Keyed by user_id (not SSRC): Discord reuses SSRCs across sessions, and a
+    // user can re-join with a new SSRC, but their transcript history should
+    // stay coherent regardless.
+    transcript_history: Arc<Mutex<HashMap<String, Vec<String>>>>,
+    sinks: Vec<Arc<dyn AudioSink + Send + Sync>>,
+    capture_mode: CaptureMode,
+    opus_buffer_map: Arc<Mutex<HashMap<u32, Vec<Vec<u8>>>>>,
+    // Keyed by user_id. Populated out-of-band by `spawn_speaker_resolution` so
+    // `resolve_speaker` never has to hold `state` across a Discord REST call.
+    user_label_cache: Arc<Mutex<HashMap<String, String>>>,
 }
 
 struct Receiver {
@@ -60,26 +116,264 @@ impl Receiver {
     pub async fn update_audio_buffer(&self, audio: Vec<i16>, ssrc: u32) {
         let state = self.state.lock().await;
 
+        for sink in &state.sinks {
+            sink.consume(ssrc, &audio).await;
+        }
+
+        let pcm = audio::to_recognizer_pcm(&audio);
+        let (speech_pcm, utterance_ended) = self.run_vad(&state, ssrc, pcm).await;
+
+        if !speech_pcm.is_empty() {
+            let mut routes = state.recognition_routes.lock().await;
+            if !routes.contains_key(&ssrc) {
+                let (speaker_label, user_id) = self.resolve_speaker(&state, ssrc).await;
+                let recognizer = state.recognizer.lock().await;
+                let route = match recognizer.start_stream().await {
+                    Some((pcm_tx, transcript_rx)) => {
+                        self.spawn_transcript_relay(
+                            ssrc,
+                            speaker_label,
+                            user_id,
+                            state.transcript_history.clone(),
+                            transcript_rx,
+                        );
+                        RecognitionRoute::Streaming(pcm_tx)
+                    }
+                    None => RecognitionRoute::Batch,
+                };
+                routes.insert(ssrc, route);
+            }
+
+            match routes.get(&ssrc).unwrap() {
+                RecognitionRoute::Streaming(pcm_tx) => {
+                    if let Err(e) = pcm_tx.send(StreamInput::Pcm(speech_pcm)) {
+                        println!("Error pushing audio to recognition stream: {}", e);
+                    }
+                }
+                RecognitionRoute::Batch => {
+                    drop(routes);
+                    let mut audio_buffer = state.audio_buffer_map.lock().await;
+                    audio_buffer
+                        .entry(ssrc)
+                        .or_insert_with(Vec::new)
+                        .extend_from_slice(&speech_pcm);
+                }
+            }
+        }
+
+        if utterance_ended {
+            // Sustained silence ends the current utterance regardless of which
+            // route is carrying it: the streaming route gets a discrete
+            // `EndUtterance` signal so it emits a final transcript and starts a
+            // fresh one without tearing down the session, while the batch
+            // fallback is finalized and cleared outright.
+            match state.recognition_routes.lock().await.get(&ssrc) {
+                Some(RecognitionRoute::Streaming(pcm_tx)) => {
+                    if let Err(e) = pcm_tx.send(StreamInput::EndUtterance) {
+                        println!("Error signalling end of utterance: {}", e);
+                    }
+                }
+                _ => {
+                    self.finalize_batch_utterance(&state, ssrc).await;
+                }
+            }
+
+            if let Some(session) = state.vad_sessions.lock().await.get_mut(&ssrc) {
+                session.vad.reset_utterance();
+            }
+        }
+    }
+
+    /// Runs freshly decoded, 16 kHz mono PCM through this speaker's voice-activity
+    /// detector frame by frame, returning the samples classified as speech (silence
+    /// between utterances is dropped, but speech stays contiguous so timing isn't
+    /// spliced) and whether sustained silence just ended an utterance.
+    async fn run_vad(&self, state: &State, ssrc: u32, pcm: Vec<i16>) -> (Vec<i16>, bool) {
+        let mut vad_sessions = state.vad_sessions.lock().await;
+        let session = vad_sessions.entry(ssrc).or_insert_with(|| VadSession {
+            vad: audio::Vad::new(),
+            carry: Vec::new(),
+        });
+
+        session.carry.extend_from_slice(&pcm);
+
+        let mut speech_pcm = Vec::new();
+        let mut utterance_ended = false;
+
+        while session.carry.len() >= audio::VAD_FRAME_SAMPLES {
+            let frame: Vec<i16> = session.carry.drain(..audio::VAD_FRAME_SAMPLES).collect();
+
+            if session.vad.classify_frame(&frame) {
+                speech_pcm.extend_from_slice(&frame);
+            }
+
+            if session.vad.utterance_ended() {
+                utterance_ended = true;
+            }
+        }
+
+        (speech_pcm, utterance_ended)
+    }
+
+    /// Writes the buffered batch-route PCM to a WAV, recognizes it, posts the
+    /// transcript, and clears the buffer. Shared by Discord's `SpeakingUpdate(false)`
+    /// and by VAD-detected sustained silence, since either can end an utterance.
+    async fn finalize_batch_utterance(&self, state: &State, ssrc: u32) {
         let mut audio_buffer = state.audio_buffer_map.lock().await;
-        let user_audio_buffer = audio_buffer.entry(ssrc).or_insert(Vec::new());
-
-        user_audio_buffer.extend_from_slice(
-            &audio
-                .iter()
-                .filter(|&x| *x != 0)
-                .collect::<Vec<&i16>>()
-                .iter()
-                .map(|&x| *x)
-                .collect::<Vec<i16>>(),
-        );
+        let user_audio_buffer = match audio_buffer.get(&ssrc) {
+            Some(buffer) if !buffer.is_empty() => buffer.clone(),
+            _ => return,
+        };
+
+        println!("Writing audio to file...");
+        self.write_audio_to_file(ssrc, user_audio_buffer.clone());
+
+        println!("Generating transcript for {}", ssrc);
+        let file = std::fs::read(format!("{}-output.wav", ssrc)).unwrap();
+        std::fs::remove_file(format!("{}-output.wav", ssrc)).unwrap();
+
+        let recognizer = state.recognizer.lock().await;
+        let transcription = recognizer.execute(file).await;
+
+        if let Some(transcription) = transcription {
+            let (speaker_label, user_id) = self.resolve_speaker(state, ssrc).await;
+
+            let history_key = user_id.unwrap_or_else(|| ssrc.to_string());
+            state
+                .transcript_history
+                .lock()
+                .await
+                .entry(history_key)
+                .or_insert_with(Vec::new)
+                .push(transcription.clone());
+
+            check_msg(
+                self.channel_id
+                    .say(
+                        &self.http,
+                        &format!("{} disse: {}", speaker_label, transcription),
+                    )
+                    .await,
+            );
+        } else {
+            println!("Failed to transcribe audio");
+        }
+
+        println!("Removing audio buffer for {}", ssrc);
+        audio_buffer.remove(&ssrc);
+    }
+
+    /// Resolves a SSRC to the Discord user speaking on it, returning both a
+    /// display label and the raw user_id to key transcript history with.
+    /// Never makes the REST call itself — that would stall every other
+    /// speaker's audio processing behind `state`'s lock — so a cache miss
+    /// kicks off `spawn_speaker_resolution` in the background and returns the
+    /// raw SSRC as a placeholder label until it completes.
+    async fn resolve_speaker(&self, state: &State, ssrc: u32) -> (String, Option<String>) {
+        let user_id = state.users_ssrc_map.lock().await.get(&ssrc).cloned();
+
+        let label = match &user_id {
+            Some(user_id) => match state.user_label_cache.lock().await.get(user_id).cloned() {
+                Some(cached) => cached,
+                None => {
+                    self.spawn_speaker_resolution(user_id.clone(), state.user_label_cache.clone());
+                    ssrc.to_string()
+                }
+            },
+            None => ssrc.to_string(),
+        };
+
+        (label, user_id)
+    }
+
+    /// Looks up a user_id via the Discord REST API and caches its `@mention`
+    /// label, off the hot audio-processing path. Future speaking turns for the
+    /// same user_id hit the cache instead of repeating the round-trip.
+    fn spawn_speaker_resolution(
+        &self,
+        user_id: String,
+        user_label_cache: Arc<Mutex<HashMap<String, String>>>,
+    ) {
+        let Ok(numeric_user_id) = user_id.parse::<u64>() else {
+            return;
+        };
+
+        let http = self.http.clone();
+
+        tokio::spawn(async move {
+            match http.get_user(numeric_user_id).await {
+                Ok(user) => {
+                    user_label_cache
+                        .lock()
+                        .await
+                        .insert(user_id, user.mention().to_string());
+                }
+                Err(e) => {
+                    println!("Error resolving user {}: {:?}", numeric_user_id, e);
+                }
+            }
+        });
+    }
+
+    /// Drains interim/final transcripts from a streaming recognition session,
+    /// posting the first one as a new message and live-editing it in place
+    /// for every transcript after that, so users see captions update in real
+    /// time instead of waiting for the whole utterance. A `is_final`
+    /// transcript closes out that utterance's message; the loop then starts a
+    /// fresh one for whatever utterance comes next on the same session,
+    /// finishing only once the sender is dropped (the speaking session ends).
+    fn spawn_transcript_relay(
+        &self,
+        ssrc: u32,
+        speaker_label: String,
+        user_id: Option<String>,
+        transcript_history: Arc<Mutex<HashMap<String, Vec<String>>>>,
+        mut transcript_rx: mpsc::UnboundedReceiver<StreamTranscript>,
+    ) {
+        let http = self.http.clone();
+        let channel_id = self.channel_id;
+
+        tokio::spawn(async move {
+            let mut message: Option<Message> = None;
+
+            while let Some(transcript) = transcript_rx.recv().await {
+                let content = format!("{} disse: {}", speaker_label, transcript.text);
+
+                if transcript.is_final {
+                    let history_key = user_id.clone().unwrap_or_else(|| ssrc.to_string());
+                    transcript_history
+                        .lock()
+                        .await
+                        .entry(history_key)
+                        .or_insert_with(Vec::new)
+                        .push(transcript.text.clone());
+                }
+
+                match &mut message {
+                    Some(msg) => {
+                        if let Err(e) = msg.edit(&http, |m| m.content(&content)).await {
+                            println!("Error editing caption message: {:?}", e);
+                        }
+                    }
+                    None => match channel_id.say(&http, &content).await {
+                        Ok(sent) => message = Some(sent),
+                        Err(e) => println!("Error sending caption message: {:?}", e),
+                    },
+                }
+
+                if transcript.is_final {
+                    message = None;
+                }
+            }
+        });
     }
 
     pub fn write_audio_to_file(&self, ssrc: u32, audio_buffer: Vec<i16>) {
         let mut wav_writer = hound::WavWriter::create(
             format!("{}-output.wav", ssrc),
             hound::WavSpec {
-                channels: 2,
-                sample_rate: 44100,
+                channels: audio::OUTPUT_CHANNELS,
+                sample_rate: audio::OUTPUT_SAMPLE_RATE,
                 bits_per_sample: 16,
                 sample_format: hound::SampleFormat::Int,
             },
@@ -142,41 +436,47 @@ impl VoiceEventHandler for Receiver {
                     println!("User {} stopped speaking", data.ssrc);
 
                     let state = self.state.lock().await;
-                    let mut audio_buffer = state.audio_buffer_map.lock().await;
-                    let user_audio_buffer = audio_buffer.get(&data.ssrc).unwrap().clone();
 
-                    if user_audio_buffer.len() == 0 {
-                        return None;
-                    }
+                    if state.capture_mode == CaptureMode::Passthrough {
+                        let frames = state.opus_buffer_map.lock().await.remove(&data.ssrc);
 
-                    println!("Writing audio to file...");
-                    self.write_audio_to_file(data.ssrc, user_audio_buffer.clone());
+                        if let Some(frames) = frames.filter(|f| !f.is_empty()) {
+                            let path = format!("{}-recording.ogg", data.ssrc);
+                            println!("Muxing {} Opus frames to {}", frames.len(), path);
 
-                    println!("Generating transcript for {}", data.ssrc);
-                    let file = std::fs::read(format!("{}-output.wav", data.ssrc)).unwrap();
-                    std::fs::remove_file(format!("{}-output.wav", data.ssrc)).unwrap();
+                            if let Err(e) = opus_record::write_ogg_opus_file(
+                                &path,
+                                &frames,
+                                audio::INPUT_CHANNELS as u8,
+                            ) {
+                                println!("Error writing Ogg/Opus recording: {}", e);
+                            }
+                        }
 
-                    let recognizer = state.recognizer.lock().await;
-                    let transcription = recognizer.execute(file).await;
+                        return None;
+                    }
 
-                    if let Some(transcription) = transcription {
-                        let channel_id = self.channel_id;
+                    // Dropping the streaming sender (by removing it here) closes the
+                    // channel; the relay task spawned in `update_audio_buffer` notices,
+                    // emits the final transcript, and finishes on its own.
+                    let was_streaming = matches!(
+                        state.recognition_routes.lock().await.remove(&data.ssrc),
+                        Some(RecognitionRoute::Streaming(_))
+                    );
 
-                        check_msg(
-                            channel_id
-                                .say(
-                                    &self.http,
-                                    &format!("{} disse: {}", data.ssrc, transcription),
-                                )
-                                .await,
-                        );
+                    if was_streaming {
+                        println!("Ending recognition stream for {}", data.ssrc);
                     } else {
-                        println!("Failed to transcribe audio");
-                        return None;
+                        // The VAD may already have finalized this utterance on sustained
+                        // silence; this is a no-op in that case since the buffer is gone.
+                        self.finalize_batch_utterance(&state, data.ssrc).await;
                     }
 
-                    println!("Removing audio buffer for {}", data.ssrc);
-                    audio_buffer.remove(&data.ssrc);
+                    state.vad_sessions.lock().await.remove(&data.ssrc);
+
+                    for sink in &state.sinks {
+                        sink.flush(data.ssrc).await;
+                    }
                 }
             },
             Ctx::VoicePacket(data) => {
@@ -199,7 +499,21 @@ impl VoiceEventHandler for Receiver {
 
                     self.update_audio_buffer(audio, data.packet.ssrc).await;
                 } else {
-                    println!("RTP packet, but no audio. Driver may not be configured to decode.");
+                    let state = self.state.lock().await;
+
+                    if state.capture_mode == CaptureMode::Passthrough {
+                        state
+                            .opus_buffer_map
+                            .lock()
+                            .await
+                            .entry(data.packet.ssrc)
+                            .or_insert_with(Vec::new)
+                            .push(data.packet.payload.to_vec());
+                    } else {
+                        println!(
+                            "RTP packet, but no audio. Driver may not be configured to decode."
+                        );
+                    }
                 }
             }
             Ctx::RtcpPacket(data) => {
@@ -208,12 +522,19 @@ impl VoiceEventHandler for Receiver {
                 println!("RTCP packet received: {:?}", data.packet);
             }
             Ctx::ClientDisconnect(ClientDisconnect { user_id, .. }) => {
-                // You can implement your own logic here to handle a user who has left the
-                // voice channel e.g., finalise processing of statistics etc.
-                // You will typically need to map the User ID to their SSRC; observed when
-                // first speaking.
-
                 println!("Client disconnected: user {:?}", user_id);
+
+                // Discord will hand this user a new SSRC if they rejoin, so drop
+                // their old SSRC mapping rather than leaving it to point at a
+                // connection that no longer exists.
+                let user_id = user_id.0.to_string();
+                self.state
+                    .lock()
+                    .await
+                    .users_ssrc_map
+                    .lock()
+                    .await
+                    .retain(|_, mapped_user_id| mapped_user_id != &user_id);
             }
             _ => {
                 // We won't be registering this struct for any more event classes.
@@ -279,6 +600,11 @@ async fn join(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
         }
     };
 
+    // Optional second argument selects the capture mode: "opus" keeps Songbird in
+    // `DecodeMode::Decrypt` and archives raw Opus frames to an Ogg file instead of
+    // decoding for recognition.
+    let capture_mode = CaptureMode::from_arg(args.single::<String>().ok().as_deref());
+
     let guild = msg.guild(&ctx.cache).unwrap();
     let guild_id = guild.id;
 
@@ -290,9 +616,16 @@ async fn join(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
     let (handler_lock, conn_result) = manager.join(guild_id, connect_to).await;
 
     if let Ok(_) = conn_result {
+        handler_lock
+            .lock()
+            .await
+            .set_config(Config::default().decode_mode(capture_mode.decode_mode()));
+
         let audio_buffer = Arc::new(Mutex::new(HashMap::new()));
         let users_ssrc_map = Arc::new(Mutex::new(HashMap::<u32, String>::new()));
-        let google_recognizer = Arc::new(Mutex::new(GoogleSpeechRecognizer {}));
+        let google_recognizer = Arc::new(Mutex::new(GoogleSpeechRecognizer::new(
+            audio::OUTPUT_SAMPLE_RATE,
+        )));
 
         let http = ctx.http.clone();
         let channel_id = msg.channel_id;
@@ -301,6 +634,15 @@ async fn join(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
             audio_buffer_map: audio_buffer.clone(),
             users_ssrc_map: users_ssrc_map.clone(),
             recognizer: google_recognizer.clone(),
+            recognition_routes: Arc::new(Mutex::new(HashMap::new())),
+            vad_sessions: Arc::new(Mutex::new(HashMap::new())),
+            transcript_history: Arc::new(Mutex::new(HashMap::new())),
+            // No sinks are registered by default; push an `Arc<dyn AudioSink>`
+            // here (e.g. `OpusRelaySink`) to bridge, archive, or stream audio.
+            sinks: Vec::new(),
+            capture_mode,
+            opus_buffer_map: Arc::new(Mutex::new(HashMap::new())),
+            user_label_cache: Arc::new(Mutex::new(HashMap::new())),
         }));
 
         let mut handler = handler_lock.lock().await;
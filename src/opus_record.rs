@@ -0,0 +1,136 @@
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use std::fs::File;
+use std::io;
+
+/// Samples per channel in one 20 ms Opus frame at Discord's 48 kHz clock,
+/// used to advance the Ogg granule position one frame at a time.
+const SAMPLES_PER_FRAME: u64 = 960;
+
+fn opus_head(channels: u8) -> Vec<u8> {
+    let mut head = Vec::with_capacity(19);
+    head.extend_from_slice(b"OpusHead");
+    head.push(1); // version
+    head.push(channels);
+    head.extend_from_slice(&0u16.to_le_bytes()); // pre-skip
+    head.extend_from_slice(&super::audio::INPUT_SAMPLE_RATE.to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+    head.push(0); // channel mapping family (mono/stereo, no extra mapping table)
+    head
+}
+
+fn opus_tags() -> Vec<u8> {
+    let mut tags = Vec::new();
+    tags.extend_from_slice(b"OpusTags");
+    let vendor = b"discord-voice-recognizer";
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor);
+    tags.extend_from_slice(&0u32.to_le_bytes()); // no user comments
+    tags
+}
+
+/// Muxes Opus frames captured byte-for-byte in `DecodeMode::Decrypt` passthrough
+/// mode into a standard Ogg/Opus file, so recordings carry no re-decode/re-encode
+/// quality loss.
+pub fn write_ogg_opus_file(path: &str, frames: &[Vec<u8>], channels: u8) -> io::Result<()> {
+    let file = File::create(path)?;
+    let mut writer = PacketWriter::new(file);
+    let serial = 1;
+
+    writer.write_packet(opus_head(channels), serial, PacketWriteEndInfo::EndPage, 0)?;
+    writer.write_packet(opus_tags(), serial, PacketWriteEndInfo::EndPage, 0)?;
+
+    let mut granule_position = 0u64;
+    let last_index = frames.len().saturating_sub(1);
+
+    for (i, frame) in frames.iter().enumerate() {
+        granule_position += SAMPLES_PER_FRAME;
+        let end_info = if i == last_index {
+            PacketWriteEndInfo::EndStream
+        } else {
+            // Ogg only stores a granule position per page, so each frame ends
+            // its own page to keep its granule position in the file.
+            PacketWriteEndInfo::EndPage
+        };
+
+        writer.write_packet(frame.clone(), serial, end_info, granule_position)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ogg::reading::PacketReader;
+
+    #[test]
+    fn opus_head_matches_the_spec_byte_layout() {
+        let head = opus_head(2);
+        assert_eq!(head.len(), 19);
+        assert_eq!(&head[0..8], b"OpusHead");
+        assert_eq!(head[8], 1); // version
+        assert_eq!(head[9], 2); // channels
+        assert_eq!(&head[10..12], 0u16.to_le_bytes()); // pre-skip
+        assert_eq!(
+            &head[12..16],
+            super::super::audio::INPUT_SAMPLE_RATE.to_le_bytes()
+        );
+        assert_eq!(&head[16..18], 0i16.to_le_bytes()); // output gain
+        assert_eq!(head[18], 0); // channel mapping family
+    }
+
+    #[test]
+    fn opus_tags_encodes_vendor_string_with_no_user_comments() {
+        let tags = opus_tags();
+        let vendor = b"discord-voice-recognizer";
+
+        assert_eq!(&tags[0..8], b"OpusTags");
+        assert_eq!(
+            u32::from_le_bytes(tags[8..12].try_into().unwrap()),
+            vendor.len() as u32
+        );
+        assert_eq!(&tags[12..12 + vendor.len()], vendor);
+        assert_eq!(
+            u32::from_le_bytes(
+                tags[12 + vendor.len()..16 + vendor.len()]
+                    .try_into()
+                    .unwrap()
+            ),
+            0
+        );
+    }
+
+    #[test]
+    fn write_ogg_opus_file_round_trips_frames_and_granule_positions() {
+        let path = std::env::temp_dir().join(format!(
+            "opus_record_test_{}_{}.ogg",
+            std::process::id(),
+            line!()
+        ));
+        let frames = vec![vec![1u8, 2, 3], vec![4u8, 5, 6], vec![7u8, 8, 9]];
+
+        write_ogg_opus_file(path.to_str().unwrap(), &frames, 2).unwrap();
+
+        let file = File::open(&path).unwrap();
+        let mut reader = PacketReader::new(file);
+
+        let head_packet = reader.read_packet().unwrap().expect("OpusHead packet");
+        assert_eq!(&head_packet.data[0..8], b"OpusHead");
+
+        let tags_packet = reader.read_packet().unwrap().expect("OpusTags packet");
+        assert_eq!(&tags_packet.data[0..8], b"OpusTags");
+
+        for (i, frame) in frames.iter().enumerate() {
+            let packet = reader
+                .read_packet()
+                .unwrap()
+                .unwrap_or_else(|| panic!("frame packet {}", i));
+            assert_eq!(&packet.data, frame);
+            assert_eq!(packet.absgp_page, SAMPLES_PER_FRAME * (i as u64 + 1));
+        }
+
+        assert!(reader.read_packet().unwrap().is_none());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,15 +1,99 @@
 use base64::prelude::*;
+use hound;
 use serde::Deserialize;
 use serenity::async_trait;
 use serenity::json::json;
 use std::env;
+use std::io::Cursor;
+use tokio::sync::mpsc;
+
+/// One transcript emitted on a streaming recognition session: `is_final`
+/// marks the end of the utterance, interim transcripts are best-effort and
+/// may still change.
+pub struct StreamTranscript {
+    pub text: String,
+    pub is_final: bool,
+}
+
+/// One message pushed into a streaming recognition session.
+pub enum StreamInput {
+    /// More 16 kHz mono PCM for the utterance in progress.
+    Pcm(Vec<i16>),
+    /// The caller's voice-activity detector found sustained silence: recognize
+    /// whatever has been buffered as a final transcript, then start a fresh
+    /// utterance on the same session instead of tearing it down. Discord's own
+    /// `SpeakingUpdate(false)` still closes the session by dropping the sender.
+    EndUtterance,
+}
 
 #[async_trait]
 pub trait VoiceRecognizer {
     async fn execute(&self, data: Vec<u8>) -> Option<String>;
+
+    /// Opens a streaming recognition session: push `StreamInput`s into the
+    /// returned sender as audio arrives and utterances end, and read
+    /// interim/final transcripts off the returned receiver. Backends without
+    /// a streaming path return `None`, and callers should fall back to
+    /// buffering the whole utterance and calling `execute` once it ends.
+    async fn start_stream(
+        &self,
+    ) -> Option<(
+        mpsc::UnboundedSender<StreamInput>,
+        mpsc::UnboundedReceiver<StreamTranscript>,
+    )> {
+        None
+    }
 }
 
-pub struct GoogleSpeechRecognizer;
+pub struct GoogleSpeechRecognizer {
+    sample_rate_hertz: u32,
+}
+
+impl GoogleSpeechRecognizer {
+    pub fn new(sample_rate_hertz: u32) -> Self {
+        Self { sample_rate_hertz }
+    }
+}
+
+/// How much newly buffered audio to wait for before running another interim
+/// recognition pass (~1 second at 16 kHz mono).
+const STREAM_INTERIM_SAMPLES: usize = 16000;
+
+/// Google's synchronous `speech:recognize` endpoint (the only one this
+/// project speaks — see `start_stream` below) rejects audio longer than
+/// ~1 minute. Force a final recognition pass and start a fresh buffer a
+/// comfortable margin before that ceiling, both so a long, uninterrupted
+/// utterance never gets rejected outright and so the re-sent buffer never
+/// grows past ~50 seconds of audio (~1.6 MB at 16 kHz mono 16-bit).
+const STREAM_MAX_BUFFER_SAMPLES: usize = 16000 * 50;
+
+fn wrap_wav(samples: &[i16], sample_rate_hertz: u32) -> Vec<u8> {
+    let mut cursor = Cursor::new(Vec::new());
+    {
+        let mut writer = hound::WavWriter::new(
+            &mut cursor,
+            hound::WavSpec {
+                channels: 1,
+                sample_rate: sample_rate_hertz,
+                bits_per_sample: 16,
+                sample_format: hound::SampleFormat::Int,
+            },
+        )
+        .expect("failed to create in-memory wav writer");
+
+        for &sample in samples {
+            writer.write_sample(sample).unwrap_or_else(|e| {
+                println!("Error writing streaming sample: {}", e);
+            });
+        }
+
+        writer.finalize().unwrap_or_else(|e| {
+            println!("Error finalizing streaming wav: {}", e);
+        });
+    }
+
+    cursor.into_inner()
+}
 
 #[derive(Deserialize, Debug)]
 pub struct GoogleSpeechAlternative {
@@ -29,7 +113,9 @@ pub struct GoogleSpeechSuccessResponse {
 
 impl GoogleSpeechSuccessResponse {
     pub fn get_best_alternative(&self) -> Option<&GoogleSpeechAlternative> {
-        self.results.first()?.alternatives
+        self.results
+            .first()?
+            .alternatives
             .iter()
             .max_by(|a, b| a.confidence.partial_cmp(&b.confidence).unwrap())
     }
@@ -49,7 +135,7 @@ impl VoiceRecognizer for GoogleSpeechRecognizer {
         let request_body = reqwest::Body::from(
             json!({
                 "config": {
-                    "sampleRateHertz": 44100,
+                    "sampleRateHertz": self.sample_rate_hertz,
                     "languageCode": "pt-BR",
                     "audioChannelCount": 1,
                 },
@@ -57,47 +143,152 @@ impl VoiceRecognizer for GoogleSpeechRecognizer {
                     "content": encoded_b64
                 }
             })
-                .to_string(),
+            .to_string(),
         );
-        let response = reqwest::Client::new()
+        // `execute` now runs repeatedly for the lifetime of a streaming session
+        // (see `start_stream`), inside a detached `tokio::spawn` task with no
+        // supervisor restarting it. A panic here would silently kill live
+        // captions for that speaker until the next `SpeakingUpdate(false)`, so
+        // every fallible step below returns `None` and logs instead.
+        let response = match reqwest::Client::new()
             .post("https://speech.googleapis.com/v1/speech:recognize")
             .headers(headers)
             .body(request_body)
             .send()
-            .await;
-
-        let response = response.unwrap_or_else(|e| {
-            println!("Error sending request: {}", e);
-            panic!();
-        });
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => {
+                println!("Error sending request: {}", e);
+                return None;
+            }
+        };
 
-        let response_json = response.json::<serde_json::Value>().await;
+        let response_json = match response.json::<serde_json::Value>().await {
+            Ok(response_json) => response_json,
+            Err(e) => {
+                println!("Error parsing response as JSON: {}", e);
+                return None;
+            }
+        };
         println!("GoogleSpeech Request full response: {:?}", response_json);
 
-        let speech_response = serde_json::from_value::<Result<GoogleSpeechSuccessResponse, ()>>(
-            response_json.unwrap(),
-        )
-            .unwrap_or_else(|e| {
-                println!("Error deserializing response: {}", e);
-                return Err(());
-            });
+        let speech_response =
+            serde_json::from_value::<Result<GoogleSpeechSuccessResponse, ()>>(response_json)
+                .unwrap_or_else(|e| {
+                    println!("Error deserializing response: {}", e);
+                    Err(())
+                });
 
         match speech_response {
-            Ok(success_response) => {
-                let best_alternative = success_response
-                    .get_best_alternative()
-                    .expect("Failed to get best alternative");
-                println!("Best alternative: {:?}", best_alternative);
+            Ok(success_response) => match success_response.get_best_alternative() {
+                Some(best_alternative) => {
+                    println!("Best alternative: {:?}", best_alternative);
 
-                let transcription = best_alternative.transcript.to_string();
-                println!("Transcription: {}", transcription);
+                    let transcription = best_alternative.transcript.to_string();
+                    println!("Transcription: {}", transcription);
 
-                Some(transcription)
-            }
+                    Some(transcription)
+                }
+                None => {
+                    println!("Error: no recognition alternatives in response");
+                    None
+                }
+            },
             Err(_) => {
                 println!("Error deserializing response");
                 None
             }
         }
     }
+
+    /// Google's true streaming endpoint (`Speech.StreamingRecognize`) is
+    /// gRPC-only; without a gRPC client in this project we approximate live
+    /// captions by re-running the unary `speech:recognize` request against a
+    /// growing buffer every ~1 second of new audio, and again as a final pass
+    /// whenever the caller's VAD reports an utterance boundary (`EndUtterance`)
+    /// or the stream itself ends. This is NOT incremental recognition — every
+    /// interim pass re-sends the whole buffer accumulated so far — so
+    /// `STREAM_MAX_BUFFER_SAMPLES` forces a final pass and a fresh buffer well
+    /// before the sync endpoint's own ~1 minute ceiling, capping both the
+    /// per-request payload and the total bytes re-sent over one utterance. A
+    /// session survives across utterance boundaries — only dropping the
+    /// sender closes it for good.
+    async fn start_stream(
+        &self,
+    ) -> Option<(
+        mpsc::UnboundedSender<StreamInput>,
+        mpsc::UnboundedReceiver<StreamTranscript>,
+    )> {
+        let (pcm_tx, mut pcm_rx) = mpsc::unbounded_channel::<StreamInput>();
+        let (transcript_tx, transcript_rx) = mpsc::unbounded_channel::<StreamTranscript>();
+        let sample_rate_hertz = self.sample_rate_hertz;
+
+        tokio::spawn(async move {
+            let recognizer = GoogleSpeechRecognizer::new(sample_rate_hertz);
+            let mut buffer: Vec<i16> = Vec::new();
+            let mut samples_since_last_interim = 0usize;
+
+            async fn finalize(
+                recognizer: &GoogleSpeechRecognizer,
+                buffer: &[i16],
+                sample_rate_hertz: u32,
+                transcript_tx: &mpsc::UnboundedSender<StreamTranscript>,
+            ) {
+                if buffer.is_empty() {
+                    return;
+                }
+
+                if let Some(text) = recognizer
+                    .execute(wrap_wav(buffer, sample_rate_hertz))
+                    .await
+                {
+                    let _ = transcript_tx.send(StreamTranscript {
+                        text,
+                        is_final: true,
+                    });
+                }
+            }
+
+            while let Some(input) = pcm_rx.recv().await {
+                match input {
+                    StreamInput::Pcm(chunk) => {
+                        samples_since_last_interim += chunk.len();
+                        buffer.extend_from_slice(&chunk);
+
+                        if buffer.len() >= STREAM_MAX_BUFFER_SAMPLES {
+                            println!(
+                                "Streaming utterance hit the {}-sample cap; finalizing early to stay under Google's sync recognize ceiling",
+                                STREAM_MAX_BUFFER_SAMPLES
+                            );
+                            finalize(&recognizer, &buffer, sample_rate_hertz, &transcript_tx).await;
+                            buffer.clear();
+                            samples_since_last_interim = 0;
+                        } else if samples_since_last_interim >= STREAM_INTERIM_SAMPLES {
+                            samples_since_last_interim = 0;
+
+                            if let Some(text) = recognizer
+                                .execute(wrap_wav(&buffer, sample_rate_hertz))
+                                .await
+                            {
+                                let _ = transcript_tx.send(StreamTranscript {
+                                    text,
+                                    is_final: false,
+                                });
+                            }
+                        }
+                    }
+                    StreamInput::EndUtterance => {
+                        finalize(&recognizer, &buffer, sample_rate_hertz, &transcript_tx).await;
+                        buffer.clear();
+                        samples_since_last_interim = 0;
+                    }
+                }
+            }
+
+            finalize(&recognizer, &buffer, sample_rate_hertz, &transcript_tx).await;
+        });
+
+        Some((pcm_tx, transcript_rx))
+    }
 }
@@ -0,0 +1,251 @@
+use audiopus::coder::Encoder;
+use audiopus::{Application, Channels, SampleRate};
+use futures::lock::Mutex;
+use serenity::async_trait;
+use std::collections::HashMap;
+
+use crate::audio::{INPUT_CHANNELS, INPUT_SAMPLE_RATE};
+
+/// Number of samples per channel in a 20 ms Opus frame at 48 kHz.
+const OPUS_FRAME_SAMPLES: usize = 960;
+/// Generous upper bound for a single encoded Opus frame, per the Opus spec.
+const OPUS_MAX_FRAME_BYTES: usize = 4000;
+
+/// A consumer of a speaker's raw decoded PCM, fed in parallel with (and
+/// independently of) the `VoiceRecognizer` path. Sinks see the audio exactly as
+/// Songbird decoded it (48 kHz interleaved stereo) so lossy relays/archives don't
+/// inherit the recognizer's 16 kHz downsampling.
+#[async_trait]
+pub trait AudioSink {
+    async fn consume(&self, ssrc: u32, pcm: &[i16]);
+    async fn flush(&self, ssrc: u32);
+}
+
+/// Where a sink's encoded Opus frames end up: another voice connection, a file,
+/// or anything else that can take a byte-addressed frame per SSRC.
+#[async_trait]
+pub trait OpusOutput {
+    async fn forward(&self, ssrc: u32, frame: Vec<u8>);
+}
+
+/// Appends raw Opus frames for a SSRC to `{ssrc}-relay.opus`, one frame after
+/// another with no container framing. Mostly useful as a default/debugging
+/// output until a real muxed format is needed.
+pub struct FileOpusOutput;
+
+#[async_trait]
+impl OpusOutput for FileOpusOutput {
+    async fn forward(&self, ssrc: u32, frame: Vec<u8>) {
+        use std::io::Write;
+
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(format!("{}-relay.opus", ssrc));
+
+        match file {
+            Ok(mut file) => {
+                if let Err(e) = file.write_all(&frame) {
+                    println!("Error writing opus frame: {}", e);
+                }
+            }
+            Err(e) => println!("Error opening opus relay file: {}", e),
+        }
+    }
+}
+
+/// Re-encodes each speaker's decoded PCM into 20 ms Opus frames and forwards
+/// them to a configurable `OpusOutput`, mirroring the voice-bridge's
+/// decode-then-re-encode relay.
+pub struct OpusRelaySink {
+    // Opus encoders carry continuous per-stream state (bitrate/VBR adaptation,
+    // LTP history), so sharing one across simultaneous speakers would
+    // interleave their frames through the same state and corrupt the
+    // encoding. Each SSRC gets its own encoder, keyed just like `pending`.
+    encoders: Mutex<HashMap<u32, Encoder>>,
+    pending: Mutex<HashMap<u32, Vec<i16>>>,
+    output: Box<dyn OpusOutput + Send + Sync>,
+}
+
+impl OpusRelaySink {
+    pub fn new(output: Box<dyn OpusOutput + Send + Sync>) -> audiopus::Result<Self> {
+        Ok(Self {
+            encoders: Mutex::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+            output,
+        })
+    }
+
+    fn new_encoder() -> audiopus::Result<Encoder> {
+        let sample_rate = match INPUT_SAMPLE_RATE {
+            48000 => SampleRate::Hz48000,
+            _ => unreachable!("audio module only decodes at 48 kHz"),
+        };
+        let channels = match INPUT_CHANNELS {
+            2 => Channels::Stereo,
+            _ => unreachable!("audio module only decodes stereo"),
+        };
+
+        Encoder::new(sample_rate, channels, Application::Audio)
+    }
+
+    async fn encode_and_forward(&self, ssrc: u32, frame: &[i16]) {
+        let mut output_buf = [0u8; OPUS_MAX_FRAME_BYTES];
+        let mut encoders = self.encoders.lock().await;
+
+        let encoder = match encoders.entry(ssrc) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => match Self::new_encoder() {
+                Ok(encoder) => entry.insert(encoder),
+                Err(e) => {
+                    println!("Error creating opus encoder for {}: {}", ssrc, e);
+                    return;
+                }
+            },
+        };
+
+        match encoder.encode(frame, &mut output_buf) {
+            Ok(len) => {
+                drop(encoders);
+                self.output.forward(ssrc, output_buf[..len].to_vec()).await;
+            }
+            Err(e) => println!("Error encoding opus frame for {}: {}", ssrc, e),
+        }
+    }
+}
+
+#[async_trait]
+impl AudioSink for OpusRelaySink {
+    async fn consume(&self, ssrc: u32, pcm: &[i16]) {
+        let frame_len = OPUS_FRAME_SAMPLES * INPUT_CHANNELS;
+
+        let mut pending = self.pending.lock().await;
+        let buffer = pending.entry(ssrc).or_insert_with(Vec::new);
+        buffer.extend_from_slice(pcm);
+
+        while buffer.len() >= frame_len {
+            let frame: Vec<i16> = buffer.drain(..frame_len).collect();
+            drop(pending);
+            self.encode_and_forward(ssrc, &frame).await;
+            pending = self.pending.lock().await;
+        }
+    }
+
+    async fn flush(&self, ssrc: u32) {
+        let mut pending = self.pending.lock().await;
+        if let Some(buffer) = pending.remove(&ssrc) {
+            if !buffer.is_empty() {
+                drop(pending);
+                // Pad the trailing partial frame with silence so the encoder
+                // always sees a full 20 ms block.
+                let frame_len = OPUS_FRAME_SAMPLES * INPUT_CHANNELS;
+                let mut frame = buffer;
+                frame.resize(frame_len, 0);
+                self.encode_and_forward(ssrc, &frame).await;
+            }
+        }
+
+        // Drop this SSRC's encoder state along with its pending buffer: if
+        // the speaker rejoins they'll be handed a fresh SSRC, so there's no
+        // continuous stream left to preserve encoder state for.
+        self.encoders.lock().await.remove(&ssrc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// Records each forwarded frame's SSRC and byte length instead of writing
+    /// anywhere, so tests can assert on `OpusRelaySink`'s accumulation/flush
+    /// behavior without touching the filesystem.
+    struct RecordingOutput {
+        forwarded: Mutex<Vec<(u32, usize)>>,
+    }
+
+    impl RecordingOutput {
+        fn new() -> Self {
+            Self {
+                forwarded: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl OpusOutput for Arc<RecordingOutput> {
+        async fn forward(&self, ssrc: u32, frame: Vec<u8>) {
+            self.forwarded.lock().await.push((ssrc, frame.len()));
+        }
+    }
+
+    fn frame_len() -> usize {
+        OPUS_FRAME_SAMPLES * INPUT_CHANNELS
+    }
+
+    fn silence(samples: usize) -> Vec<i16> {
+        vec![0i16; samples]
+    }
+
+    #[tokio::test]
+    async fn consume_buffers_a_partial_frame_without_forwarding() {
+        let output = Arc::new(RecordingOutput::new());
+        let sink = OpusRelaySink::new(Box::new(output.clone())).unwrap();
+
+        sink.consume(1, &silence(frame_len() - 1)).await;
+
+        assert!(output.forwarded.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn consume_forwards_exactly_one_frame_once_enough_samples_accumulate() {
+        let output = Arc::new(RecordingOutput::new());
+        let sink = OpusRelaySink::new(Box::new(output.clone())).unwrap();
+
+        sink.consume(1, &silence(frame_len())).await;
+
+        let forwarded = output.forwarded.lock().await;
+        assert_eq!(forwarded.len(), 1);
+        assert_eq!(forwarded[0].0, 1);
+    }
+
+    #[tokio::test]
+    async fn consume_keeps_simultaneous_speakers_independent() {
+        let output = Arc::new(RecordingOutput::new());
+        let sink = OpusRelaySink::new(Box::new(output.clone())).unwrap();
+
+        // SSRC 2 accumulates a full frame while SSRC 1 still has a partial
+        // one pending; only SSRC 2 should have forwarded anything, proving
+        // the two streams (and their encoders) don't interfere.
+        sink.consume(1, &silence(frame_len() - 1)).await;
+        sink.consume(2, &silence(frame_len())).await;
+
+        let forwarded = output.forwarded.lock().await;
+        assert_eq!(forwarded.len(), 1);
+        assert_eq!(forwarded[0].0, 2);
+    }
+
+    #[tokio::test]
+    async fn flush_pads_a_trailing_partial_frame_with_silence_and_forwards_it() {
+        let output = Arc::new(RecordingOutput::new());
+        let sink = OpusRelaySink::new(Box::new(output.clone())).unwrap();
+
+        sink.consume(1, &silence(frame_len() / 2)).await;
+        sink.flush(1).await;
+
+        assert_eq!(output.forwarded.lock().await.len(), 1);
+        // The per-SSRC encoder is dropped on flush so a later SSRC reuse
+        // starts from fresh encoder state rather than stale stream history.
+        assert!(!sink.encoders.lock().await.contains_key(&1));
+    }
+
+    #[tokio::test]
+    async fn flush_on_an_empty_buffer_forwards_nothing() {
+        let output = Arc::new(RecordingOutput::new());
+        let sink = OpusRelaySink::new(Box::new(output.clone())).unwrap();
+
+        sink.flush(1).await;
+
+        assert!(output.forwarded.lock().await.is_empty());
+    }
+}